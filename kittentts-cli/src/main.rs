@@ -15,6 +15,8 @@ struct Cli {
     wav: PathBuf,
     #[arg(short, long)]
     phonems: bool,
+    #[arg(short, long, value_name = "TOKENS_FILE")]
+    tokens: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -38,6 +40,12 @@ fn main() -> Result<()> {
     };
 
     let mut model = KittenModel::model_builtin(KittenVoice::default())?;
+    if let Some(tokens_path) = cli.tokens {
+        model = model.with_tokens(KittenModel::tokens_from_file(tokens_path)?)?;
+        if !cli.phonems {
+            model.validate_against_phonemizer()?;
+        }
+    }
     let out = if cli.phonems {
         model.generate_from_phonems(text.clone())?
     } else {
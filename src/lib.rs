@@ -1,10 +1,12 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt::Display,
+    fs,
     io::{self, Cursor},
     path::Path,
 };
 
+use markup::parse_speed_markup;
 use ndarray::{Array1, Array2, ArrayView1, Axis, s};
 use npyz::npz::NpzArchive;
 use ort::{
@@ -14,12 +16,29 @@ use ort::{
 use phonemize::Phonemizer;
 use thiserror::Error;
 
+pub mod markup;
 pub mod phonemize;
 pub mod wav;
 
 static MODEL: &[u8] = include_bytes!("../model-files/kitten_tts_nano_v0_1.onnx");
 static VOICES: &[u8] = include_bytes!("../model-files/voices.npz");
 
+/// Average number of `duration` frames the model assigns to a single token at speed 1.0.
+///
+/// Calibrated on the default voice (`expr-voice-5-m`) at speed 1.0 over ten short English
+/// sentences run through the built-in phonemizer, one of them the `estimate_duration` test
+/// phrase: the `duration` output summed to 1630 frames for 483 tokens, a mean of 3.37, rounded
+/// to 3.4 (the test phrase alone averages 3.64). The other built-in voices speak faster, at
+/// 2.1 to 2.9 frames per token on the same sentences, so for them the estimate runs long.
+/// The model divides durations by `speed`, so frames scale with `1 / speed`.
+pub const FRAMES_PER_TOKEN: f32 = 3.4;
+/// Number of waveform samples the model generates for every `duration` frame.
+pub const SAMPLES_PER_FRAME: usize = 600;
+/// Number of silent samples added around every generated waveform, one on each side.
+pub const PADDING_SAMPLES: usize = 2;
+/// Speed used for text outside of any `[[speed=X]]` markup.
+pub const DEFAULT_SPEED: f32 = 1.0;
+
 #[derive(Error, Debug, Clone)]
 pub enum KittenError {
     #[error("failed to load model: {0}")]
@@ -28,6 +47,8 @@ pub enum KittenError {
     ModelExecute(String),
     #[error("failed to save model result: {0}")]
     ModelResultSave(String),
+    #[error("failed to load tokens: {0}")]
+    TokensLoad(String),
 }
 
 #[derive(Debug, Clone, Default)]
@@ -254,6 +275,95 @@ impl KittenModel {
         ])
     }
 
+    /// Parses a token table from text, one `<id>\t<char>` entry per line.
+    ///
+    /// Everything after the tab is taken verbatim, so whitespace characters such
+    /// as the space token can be expressed. Empty lines are skipped. Each character may
+    /// appear only once, but several characters may share an id, e.g. quote variants.
+    pub fn tokens_from_str(text: &str) -> Result<KittenTokens, KittenError> {
+        let mut tokens = KittenTokens::new();
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let Some((id, token)) = line.split_once('\t') else {
+                return Err(KittenError::TokensLoad(format!(
+                    "line {number}: expected `<id>\\t<char>`"
+                )));
+            };
+            let mut chars = token.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                return Err(KittenError::TokensLoad(format!(
+                    "line {number}: expected a single character, got {token:?}"
+                )));
+            };
+            let id = id
+                .trim()
+                .parse::<i64>()
+                .map_err(|e| KittenError::TokensLoad(format!("line {number}: {e}")))?;
+            if tokens.insert(c, id).is_some() {
+                return Err(KittenError::TokensLoad(format!(
+                    "line {number}: duplicate token {c:?}"
+                )));
+            }
+        }
+
+        Self::validate_tokens(&tokens)?;
+        Ok(tokens)
+    }
+
+    /// Reads a token table from a file in the format accepted by [`KittenModel::tokens_from_str`].
+    pub fn tokens_from_file<P: AsRef<Path>>(path: P) -> Result<KittenTokens, KittenError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| KittenError::TokensLoad(format!("{}: {e}", path.display())))?;
+        Self::tokens_from_str(&text)
+    }
+
+    /// Replaces the built-in token table, e.g. for a model trained with a different vocabulary.
+    ///
+    /// The model's vocabulary size is not exposed by the ONNX graph, so the table is only
+    /// checked to be non-empty and to use non-negative ids. Use
+    /// [`KittenModel::validate_against_phonemizer`] when the table is meant for
+    /// [`KittenModel::generate`] rather than a custom phonemizer.
+    pub fn with_tokens(mut self, tokens: KittenTokens) -> Result<Self, KittenError> {
+        Self::validate_tokens(&tokens)?;
+        self.tokens = tokens;
+        Ok(self)
+    }
+
+    /// Checks that the token table covers every character the built-in phonemizer emits.
+    ///
+    /// Missing characters would otherwise be silently dropped by [`KittenModel::generate`].
+    pub fn validate_against_phonemizer(&self) -> Result<(), KittenError> {
+        let missing: BTreeSet<char> = self
+            .phonemizer
+            .phonemes()
+            .chain([' '])
+            .filter(|c| !self.tokens.contains_key(c))
+            .collect();
+        if !missing.is_empty() {
+            return Err(KittenError::TokensLoad(format!(
+                "token table is missing phonemes {missing:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_tokens(tokens: &KittenTokens) -> Result<(), KittenError> {
+        if tokens.is_empty() {
+            return Err(KittenError::TokensLoad("token table is empty".to_string()));
+        }
+        if let Some((c, id)) = tokens.iter().find(|(_, id)| **id < 0) {
+            return Err(KittenError::TokensLoad(format!(
+                "negative id {id} for token {c:?}"
+            )));
+        }
+        Ok(())
+    }
+
     pub fn model_from_files<P: AsRef<Path>>(
         model_path: P,
         voices_path: P,
@@ -322,18 +432,85 @@ impl KittenModel {
         })
     }
 
+    /// Number of tokens `phonems` maps to; characters missing from the token table are skipped.
+    pub fn count_tokens(&self, phonems: &str) -> usize {
+        phonems
+            .chars()
+            .filter(|c| self.tokens.contains_key(c))
+            .count()
+    }
+
+    /// Rough number of waveform samples generated for `token_count` tokens.
+    ///
+    /// Derived from [`FRAMES_PER_TOKEN`] and [`SAMPLES_PER_FRAME`], scaled by `1 / speed`, so
+    /// expect it to be off by up to about 20%. Useful for progress indicators and
+    /// pre-allocating buffers. Returns `None` unless `speed` is finite and positive; very
+    /// small speeds saturate at `usize::MAX`.
+    pub fn estimate_samples(token_count: usize, speed: f32) -> Option<usize> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return None;
+        }
+        let frames = (token_count as f64 * FRAMES_PER_TOKEN as f64 / speed as f64).round();
+        // Float to int casts saturate, so this cannot overflow.
+        Some((frames * SAMPLES_PER_FRAME as f64 + PADDING_SAMPLES as f64) as usize)
+    }
+
+    /// Rough duration in seconds of the audio generated for `token_count` tokens at `speed`
+    /// when played back at `sample_rate`, which defaults to [`wav::DEFAULT_SAMPLE_RATE`] like
+    /// [`wav::save_array1_f32_as_wav`].
+    pub fn estimate_duration(
+        token_count: usize,
+        speed: f32,
+        sample_rate: Option<u32>,
+    ) -> Option<f32> {
+        let sample_rate = sample_rate.unwrap_or(wav::DEFAULT_SAMPLE_RATE);
+        Self::estimate_samples(token_count, speed).map(|s| s as f32 / sample_rate as f32)
+    }
+
+    /// Generates speech for `text`, honouring inline `[[speed=X]]...[[/speed]]` markup.
+    ///
+    /// Each segment is synthesized with its own speed and the results are concatenated;
+    /// unmarked text uses [`DEFAULT_SPEED`]. See [`markup::parse_speed_markup`]. Text that maps
+    /// to no tokens at all yields empty arrays without running the model.
     pub fn generate(&mut self, text: String) -> Result<(Array1<f32>, Array1<i64>), KittenError> {
-        let phonems: Vec<String> = text
-            .split_whitespace()
-            .flat_map(|word| self.phonemizer.phonemize(word))
+        // Segments that map to no tokens (e.g. a lone unknown word) are skipped rather than
+        // sent to the model as an empty input.
+        let segments: Vec<(String, f32)> = parse_speed_markup(&text, DEFAULT_SPEED)
+            .into_iter()
+            .map(|segment| {
+                let phonems: Vec<String> = segment
+                    .text
+                    .split_whitespace()
+                    .flat_map(|word| self.phonemizer.phonemize(word))
+                    .collect();
+                (phonems.join(" "), segment.speed)
+            })
+            .filter(|(phonemized, _)| self.count_tokens(phonemized) > 0)
             .collect();
-        let phonemized = phonems.join(" ");
-        self.generate_from_phonems(phonemized)
+
+        let mut waveform = Vec::new();
+        let mut duration = Vec::new();
+        for (phonemized, speed) in segments {
+            let (segment_waveform, segment_duration) =
+                self.generate_from_phonems_with_speed(phonemized, speed)?;
+            waveform.extend(&segment_waveform);
+            duration.extend(&segment_duration);
+        }
+
+        Ok((Array1::from_vec(waveform), Array1::from_vec(duration)))
     }
 
     pub fn generate_from_phonems(
         &mut self,
         phonems: String,
+    ) -> Result<(Array1<f32>, Array1<i64>), KittenError> {
+        self.generate_from_phonems_with_speed(phonems, DEFAULT_SPEED)
+    }
+
+    pub fn generate_from_phonems_with_speed(
+        &mut self,
+        phonems: String,
+        speed: f32,
     ) -> Result<(Array1<f32>, Array1<i64>), KittenError> {
         let text_array: Array1<i64> = phonems
             .chars()
@@ -347,7 +524,7 @@ impl KittenModel {
         let style_input: Array2<f32> = self.voice.clone().insert_axis(Axis(0));
         let style_tensor = Tensor::from_array(style_input)
             .map_err(|e| KittenError::ModelExecute(e.to_string()))?;
-        let speed_tensor = Tensor::from_array(Array1::from_vec(vec![1.0_f32]))
+        let speed_tensor = Tensor::from_array(Array1::from_vec(vec![speed]))
             .map_err(|e| KittenError::ModelExecute(e.to_string()))?;
 
         let outputs = self
@@ -370,9 +547,10 @@ impl KittenModel {
             .into_dimensionality()
             .map_err(|e| KittenError::ModelExecute(e.to_string()))?;
 
-        let mut padded = Array1::zeros(waveform.len() + 2);
+        let pad = PADDING_SAMPLES / 2;
+        let mut padded = Array1::zeros(waveform.len() + PADDING_SAMPLES);
         padded
-            .slice_mut(s![1..waveform.len() + 1])
+            .slice_mut(s![pad..waveform.len() + pad])
             .assign(&waveform);
 
         Ok((padded, duration.to_owned()))
@@ -404,6 +582,50 @@ mod tests {
         assert_eq!(res.is_ok(), true);
     }
 
+    #[test]
+    fn tokens_from_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("tokens.txt");
+        fs::write(&file_path, "0\t$\n16\t \n\n83\tə\n").unwrap();
+
+        let tokens = KittenModel::tokens_from_file(&file_path).unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[&' '], 16);
+        assert_eq!(tokens[&'ə'], 83);
+
+        fs::write(&file_path, "0\t$\r\n16\t \r\n\r\n83\tə\r").unwrap();
+        let crlf_tokens = KittenModel::tokens_from_file(&file_path).unwrap();
+        assert_eq!(crlf_tokens, tokens);
+    }
+
+    #[test]
+    fn tokens_invalid() {
+        assert!(KittenModel::tokens_from_str("").is_err());
+        assert!(KittenModel::tokens_from_str("1 a").is_err());
+        assert!(KittenModel::tokens_from_str("1\tab").is_err());
+        assert!(KittenModel::tokens_from_str("-1\ta").is_err());
+        assert!(KittenModel::tokens_from_str("x\ta").is_err());
+        assert!(KittenModel::tokens_from_str("1\ta\n2\ta").is_err());
+        assert!(KittenModel::tokens_from_str("1\ta\n1\tb").is_ok());
+    }
+
+    #[test]
+    fn with_tokens() {
+        let model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        assert!(model.with_tokens(KittenTokens::new()).is_err());
+
+        let model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        let tokens = KittenModel::tokens_from_str("0\t$\n16\t ").unwrap();
+        let model = model.with_tokens(tokens).unwrap();
+        assert!(model.validate_against_phonemizer().is_err());
+
+        let model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        let mut model = model.with_tokens(KittenModel::get_tokens()).unwrap();
+        assert!(model.validate_against_phonemizer().is_ok());
+        let res = model.generate_from_phonems("ðɪs haɪ kwɔlᵻɾi".to_string());
+        assert!(res.is_ok());
+    }
+
     #[test]
     fn generate_from_phonems() {
         let model = KittenModel::model_builtin(KittenVoice::default());
@@ -424,6 +646,90 @@ mod tests {
         assert_eq!(res.is_ok(), true);
     }
 
+    #[test]
+    fn estimate_duration() {
+        let phonems = "ðɪs haɪ kwɔlᵻɾi tiːtiːɛs mɑːdəl wɜːks wɪðaʊt ɐ dʒiːpiːjuː ";
+        let mut model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        let token_count = model.count_tokens(phonems);
+        let (waveform, duration) = model.generate_from_phonems(phonems.to_string()).unwrap();
+
+        let frames = duration.sum() as usize;
+        assert_eq!(waveform.len(), frames * SAMPLES_PER_FRAME + PADDING_SAMPLES);
+        let frames_per_token = frames as f32 / token_count as f32;
+        let error = (frames_per_token - FRAMES_PER_TOKEN).abs() / FRAMES_PER_TOKEN;
+        assert!(error < 0.2, "{frames_per_token} frames per token");
+
+        let actual = waveform.len() as f32 / wav::DEFAULT_SAMPLE_RATE as f32;
+        let estimate = KittenModel::estimate_duration(token_count, DEFAULT_SPEED, None).unwrap();
+        let error = (estimate - actual).abs() / actual;
+        assert!(error < 0.2, "estimate {estimate}s vs actual {actual}s");
+
+        let slow = KittenModel::estimate_samples(token_count, 0.5).unwrap();
+        let normal = KittenModel::estimate_samples(token_count, DEFAULT_SPEED).unwrap();
+        assert!(slow.abs_diff(2 * normal) <= SAMPLES_PER_FRAME + PADDING_SAMPLES);
+    }
+
+    #[test]
+    fn estimate_invalid_speed() {
+        assert_eq!(KittenModel::estimate_samples(10, 0.0), None);
+        assert_eq!(KittenModel::estimate_samples(10, -1.0), None);
+        assert_eq!(KittenModel::estimate_samples(10, f32::NAN), None);
+        assert_eq!(KittenModel::estimate_samples(10, f32::INFINITY), None);
+        assert_eq!(KittenModel::estimate_duration(10, 0.0, None), None);
+        assert_eq!(KittenModel::estimate_samples(10, 1e-30), Some(usize::MAX));
+        assert_eq!(
+            KittenModel::estimate_samples(0, DEFAULT_SPEED),
+            Some(PADDING_SAMPLES)
+        );
+    }
+
+    #[test]
+    fn generate_mixed_speed() {
+        let mut model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        let (normal, _) = model
+            .generate("This model works [[speed=1.0]]slowly[[/speed]]".to_string())
+            .unwrap();
+        let (slow, _) = model
+            .generate("This model works [[speed=0.5]]slowly[[/speed]]".to_string())
+            .unwrap();
+        assert!(slow.len() > normal.len());
+    }
+
+    #[test]
+    fn generate_markup_without_tokens() {
+        let mut model = KittenModel::model_builtin(KittenVoice::default()).unwrap();
+        let tokens_of = |model: &KittenModel, text: &str| {
+            let phonems: Vec<String> = text
+                .split_whitespace()
+                .flat_map(|word| model.phonemizer.phonemize(word))
+                .collect();
+            model.count_tokens(&phonems.join(" "))
+        };
+
+        let (waveform, duration) = model
+            .generate("Say [[speed=0.5]]this[[/speed]].".to_string())
+            .unwrap();
+        let expected =
+            tokens_of(&model, "Say") + tokens_of(&model, "this") + tokens_of(&model, ".");
+        assert_eq!(duration.len(), expected);
+        assert!(!waveform.is_empty());
+
+        let (_, duration) = model
+            .generate("This model works [[speed=0.5]]§[[/speed]]".to_string())
+            .unwrap();
+        assert_eq!(duration.len(), tokens_of(&model, "This model works"));
+
+        for text in [
+            "§ [[speed=0.5]]§§[[/speed]] ~~",
+            "[[speed=0.5]][[/speed]]",
+            "",
+        ] {
+            let (waveform, duration) = model.generate(text.to_string()).unwrap();
+            assert!(waveform.is_empty());
+            assert!(duration.is_empty());
+        }
+    }
+
     #[test]
     fn save() {
         let model = KittenModel::model_builtin(KittenVoice::default());
@@ -0,0 +1,160 @@
+use std::ops::RangeInclusive;
+
+const OPEN: &str = "[[";
+const CLOSE: &str = "]]";
+const SPEED_PREFIX: &str = "speed=";
+const SPEED_END: &str = "/speed";
+
+/// Speeds accepted in `[[speed=X]]` tags; values outside would make the model produce
+/// absurdly long or short audio.
+pub const SPEED_RANGE: RangeInclusive<f32> = 0.25..=4.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedSegment {
+    pub text: String,
+    pub speed: f32,
+}
+
+enum Tag {
+    Speed(f32),
+    SpeedEnd,
+}
+
+/// Splits `text` into segments by inline `[[speed=X]]...[[/speed]]` markup.
+///
+/// Text outside any tag gets `default_speed`. Tags may be nested, and an unclosed tag
+/// lasts until the end of the text. Anything that is not a valid tag, including a stray
+/// `[[/speed]]` or a speed outside [`SPEED_RANGE`], is kept as literal text. Whitespace-only
+/// segments are dropped.
+pub fn parse_speed_markup(text: &str, default_speed: f32) -> Vec<SpeedSegment> {
+    let mut segments = Vec::new();
+    let mut speeds = vec![default_speed];
+    let mut current = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(OPEN) {
+        current.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let tag = rest[OPEN.len()..].find(CLOSE).and_then(|end| {
+            match parse_tag(&rest[OPEN.len()..OPEN.len() + end])? {
+                Tag::SpeedEnd if speeds.len() == 1 => None,
+                tag => Some((tag, OPEN.len() + end + CLOSE.len())),
+            }
+        });
+
+        match tag {
+            Some((tag, tag_len)) => {
+                push_segment(&mut segments, &mut current, speeds[speeds.len() - 1]);
+                match tag {
+                    Tag::Speed(speed) => speeds.push(speed),
+                    Tag::SpeedEnd => {
+                        speeds.pop();
+                    }
+                }
+                rest = &rest[tag_len..];
+            }
+            None => {
+                // Only consume the first bracket so a valid tag starting at the next one still parses.
+                current.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    current.push_str(rest);
+    push_segment(&mut segments, &mut current, speeds[speeds.len() - 1]);
+
+    segments
+}
+
+fn parse_tag(inner: &str) -> Option<Tag> {
+    if inner == SPEED_END {
+        return Some(Tag::SpeedEnd);
+    }
+    let speed = inner.strip_prefix(SPEED_PREFIX)?.parse::<f32>().ok()?;
+    SPEED_RANGE.contains(&speed).then_some(Tag::Speed(speed))
+}
+
+fn push_segment(segments: &mut Vec<SpeedSegment>, current: &mut String, speed: f32) {
+    let text = std::mem::take(current);
+    if !text.trim().is_empty() {
+        segments.push(SpeedSegment { text, speed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(text: &str, speed: f32) -> SpeedSegment {
+        SpeedSegment {
+            text: text.to_string(),
+            speed,
+        }
+    }
+
+    #[test]
+    fn plain() {
+        let res = parse_speed_markup("hello world", 1.0);
+        assert_eq!(res, vec![segment("hello world", 1.0)]);
+    }
+
+    #[test]
+    fn two_speeds() {
+        let res = parse_speed_markup("say this [[speed=0.7]]slowly[[/speed]] please", 1.0);
+        assert_eq!(
+            res,
+            vec![
+                segment("say this ", 1.0),
+                segment("slowly", 0.7),
+                segment(" please", 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let res = parse_speed_markup("a [[speed=0.5]]b [[speed=2]]c[[/speed]] d[[/speed]] e", 1.0);
+        assert_eq!(
+            res,
+            vec![
+                segment("a ", 1.0),
+                segment("b ", 0.5),
+                segment("c", 2.0),
+                segment(" d", 0.5),
+                segment(" e", 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed() {
+        let res = parse_speed_markup("a [[speed=0.5]]b c", 1.0);
+        assert_eq!(res, vec![segment("a ", 1.0), segment("b c", 0.5)]);
+
+        let res = parse_speed_markup("a [[speed=0.5 b", 1.0);
+        assert_eq!(res, vec![segment("a [[speed=0.5 b", 1.0)]);
+    }
+
+    #[test]
+    fn malformed() {
+        let res = parse_speed_markup("a[[/speed]] [[speed=fast]]b [[speed=-1]]c [[foo]]", 1.0);
+        assert_eq!(
+            res,
+            vec![segment(
+                "a[[/speed]] [[speed=fast]]b [[speed=-1]]c [[foo]]",
+                1.0
+            )]
+        );
+
+        let res = parse_speed_markup("[[[speed=2]]a", 1.0);
+        assert_eq!(res, vec![segment("[", 1.0), segment("a", 2.0)]);
+
+        let text = "a [[speed=0.0001]]b [[speed=1e-30]]c [[speed=4.5]]d [[speed=NaN]]e";
+        let res = parse_speed_markup(text, 1.0);
+        assert_eq!(res, vec![segment(text, 1.0)]);
+
+        let res = parse_speed_markup("[[speed=0.25]]a[[/speed]] [[speed=4]]b", 1.0);
+        assert_eq!(res, vec![segment("a", 0.25), segment("b", 4.0)]);
+    }
+}
@@ -104,6 +104,11 @@ impl Phonemizer {
         Ok(Self { dict, ipa })
     }
 
+    /// Characters [`Phonemizer::phonemize`] can emit for words found in the dictionary.
+    pub fn phonemes(&self) -> impl Iterator<Item = char> + '_ {
+        self.ipa.values().flat_map(|p| p.chars())
+    }
+
     pub fn phonemize(&self, word: &str) -> Option<String> {
         let lower_case = word.to_lowercase();
         let upper_case = word.to_uppercase();
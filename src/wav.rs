@@ -4,7 +4,39 @@ use std::{
     path::Path,
 };
 
-use ndarray::Array1;
+use ndarray::{Array1, s};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 22000;
+
+/// Applies a linear fade-in and fade-out envelope to `data` in place.
+///
+/// If the fades together are longer than the clip they are scaled down proportionally
+/// so they do not overlap. The first and last samples are always zero when the
+/// corresponding fade is nonzero.
+pub fn apply_fade(data: &mut Array1<f32>, fade_in_ms: u32, fade_out_ms: u32, sample_rate: u32) {
+    let len = data.len();
+    let to_samples = |ms: u32| ms as u64 * sample_rate as u64 / 1000;
+    let (mut fade_in, mut fade_out) = (to_samples(fade_in_ms), to_samples(fade_out_ms));
+    let total = fade_in + fade_out;
+    if total > len as u64 {
+        fade_in = fade_in * len as u64 / total;
+        fade_out = len as u64 - fade_in;
+    }
+    let (fade_in, fade_out) = (fade_in as usize, fade_out as usize);
+
+    for (i, sample) in data.slice_mut(s![..fade_in]).iter_mut().enumerate() {
+        *sample *= i as f32 / fade_in as f32;
+    }
+
+    for (i, sample) in data
+        .slice_mut(s![len - fade_out..])
+        .iter_mut()
+        .rev()
+        .enumerate()
+    {
+        *sample *= i as f32 / fade_out as f32;
+    }
+}
 
 pub fn save_array1_f32_as_wav<P: AsRef<Path>>(
     data: &Array1<f32>,
@@ -12,7 +44,7 @@ pub fn save_array1_f32_as_wav<P: AsRef<Path>>(
     sample_rate: Option<u32>,
 ) -> Result<(), io::Error> {
     let mut file = File::create(out_path)?;
-    let sample_rate = sample_rate.unwrap_or(22000);
+    let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
 
     let num_samples = data.len() as u32;
     let num_channels = 1u16;
@@ -44,3 +76,28 @@ pub fn save_array1_f32_as_wav<P: AsRef<Path>>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fade() {
+        let mut data = Array1::from_elem(1000, 1.0_f32);
+        apply_fade(&mut data, 10, 10, 22000);
+        assert_eq!(data[0], 0.0);
+        assert_eq!(data[data.len() - 1], 0.0);
+        assert_eq!(data[500], 1.0);
+        assert!(data[1] > 0.0 && data[1] < data[2]);
+    }
+
+    #[test]
+    fn fade_longer_than_clip() {
+        let mut data = Array1::from_elem(100, 1.0_f32);
+        apply_fade(&mut data, 1000, 1000, 22000);
+        assert_eq!(data[0], 0.0);
+        assert_eq!(data[data.len() - 1], 0.0);
+        assert!(data[49] > 0.9);
+        assert!(data[50] > 0.9);
+    }
+}